@@ -0,0 +1,109 @@
+//! The buffered, seek-tracking data file shared by [`Table`] and [`TimeseriesTable`].
+//!
+//! Both tables append-serialize values to a data file sitting behind a [`BufWriter`], and both
+//! need to track whether a read has moved the underlying file's cursor away from the end before
+//! the next append can rely on the buffered writer's own position instead of reseeking. This
+//! module pulls that bookkeeping out into one place instead of each table keeping its own copy.
+//!
+//! [`Table`]: ::untyped::Table
+//! [`TimeseriesTable`]: ::timeseries::TimeseriesTable
+
+use error::{Error, Result};
+
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+
+/// A table's data file, wrapped with buffered writes and seek tracking.
+///
+/// Every offset passed to these methods is a *logical* offset, relative to the end of the fixed
+/// prefix (`prefix_len`) at the start of the file — a signature, and for an encrypted [`Table`],
+/// a nonce. Encryption itself is layered on top by the caller; this type only moves bytes.
+///
+/// [`Table`]: ::untyped::Table
+pub(crate) struct DataFile {
+  file: RefCell<BufWriter<File>>,
+  // set whenever a read moves the file's cursor away from the end of the file, so the next
+  // append knows it has to seek back before it can append for free
+  needs_seek: Cell<bool>,
+  prefix_len: u64,
+}
+
+impl DataFile {
+  /// Wraps `file`, buffering writes with `capacity` bytes of capacity.
+  ///
+  /// `len` is the table's current logical length; a freshly-opened file handle's cursor sits
+  /// right after the prefix, so if there's already data on disk (`len > 0`), the first append
+  /// needs to seek to the end before it can start appending.
+  pub(crate) fn new(file: File, capacity: usize, prefix_len: u64, len: u64) -> DataFile {
+    DataFile {
+      file: RefCell::new(BufWriter::with_capacity(capacity, file)),
+      needs_seek: Cell::new(len > 0),
+      prefix_len,
+    }
+  }
+
+  /// Reads `len` bytes starting at logical `offset` out of the data file.
+  ///
+  /// Flushes any buffered writes first, since the bytes being read may still be sitting in the
+  /// write buffer rather than on disk.
+  pub(crate) fn read_at(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
+    let mut writer = self.file.borrow_mut();
+    writer.flush().map_err(Error::Io)?;
+
+    let mut file = writer.get_ref();
+    file.seek(SeekFrom::Start(self.prefix_len + offset)).map_err(Error::Io)?;
+    let mut data = vec![0; len as usize];
+    file.read_exact(&mut data).map_err(Error::Io)?;
+
+    // the read moved the shared file cursor away from the end of the file
+    self.needs_seek.set(true);
+
+    Ok(data)
+  }
+
+  /// Appends `bytes` at logical offset `old_len`, the table's length before this append.
+  ///
+  /// Only reseeks if a prior read moved the cursor away from the end of the file; otherwise the
+  /// buffered writer's own position already tracks the end and this can append for free.
+  pub(crate) fn append(&self, old_len: u64, bytes: &[u8]) -> Result<()> {
+    let mut file = self.file.borrow_mut();
+    if self.needs_seek.replace(false) {
+      file.seek(SeekFrom::Start(self.prefix_len + old_len)).map_err(Error::Io)?;
+    }
+    file.write_all(bytes).map_err(Error::Io)?;
+    Ok(())
+  }
+
+  /// Overwrites `bytes` starting at logical `offset`, which may be anywhere in the file.
+  ///
+  /// Flushes any buffered writes first, so the write lands after everything written so far, and
+  /// marks the cursor as moved, since `offset` isn't necessarily the end of the file.
+  pub(crate) fn write_at(&self, offset: u64, bytes: &[u8]) -> Result<()> {
+    let mut writer = self.file.borrow_mut();
+    writer.flush().map_err(Error::Io)?;
+
+    let mut file = writer.get_ref();
+    file.seek(SeekFrom::Start(self.prefix_len + offset)).map_err(Error::Io)?;
+    file.write_all(bytes).map_err(Error::Io)?;
+    self.needs_seek.set(true);
+
+    Ok(())
+  }
+
+  /// Flushes any buffered writes.
+  pub(crate) fn flush(&self) -> Result<()> {
+    self.file.borrow_mut().flush().map_err(Error::Io)
+  }
+
+  /// Resizes the file so its logical length becomes `len`, flushing any buffered writes first.
+  ///
+  /// Marks the cursor as moved, since the buffered writer's tracked position may no longer line
+  /// up with the new end of the file.
+  pub(crate) fn set_len(&self, len: u64) -> Result<()> {
+    self.flush()?;
+    self.file.borrow().get_ref().set_len(self.prefix_len + len).map_err(Error::Io)?;
+    self.needs_seek.set(true);
+    Ok(())
+  }
+}
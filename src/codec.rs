@@ -0,0 +1,94 @@
+//! Pluggable serialization backends for a [`Table`]'s index and data files.
+//!
+//! [`Table`]: ::untyped::Table
+
+use error::{Error, Result};
+
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// A serialization backend a [`Table`] can be opened with.
+///
+/// A table records which codec it was written with in its file signature (see [`Table::open`]),
+/// so reopening it with a different codec is rejected with [`Error::Codec`] rather than silently
+/// producing garbage.
+///
+/// [`Table`]: ::untyped::Table
+/// [`Table::open`]: ::untyped::Table::open
+/// [`Error::Codec`]: ::error::Error::Codec
+pub trait Codec {
+  /// A one-byte identifier for this codec, stored in the file signature's flags.
+  fn id() -> u8;
+
+  /// Encodes `value` to bytes suitable for writing to a table's data or index file.
+  fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>>;
+
+  /// Decodes bytes previously produced by [`encode`] back into a value.
+  ///
+  /// [`encode`]: Codec::encode
+  fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V>;
+}
+
+/// The original MessagePack-backed codec, and the default for [`Table`].
+///
+/// [`Table`]: ::untyped::Table
+pub struct MsgPack;
+
+impl Codec for MsgPack {
+  fn id() -> u8 {
+    0
+  }
+
+  fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>> {
+    ::serde_msgpack::to_vec(value).map_err(|e| Error::Codec(Box::new(e)))
+  }
+
+  fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V> {
+    ::serde_msgpack::from_slice(bytes).map_err(|e| Error::Codec(Box::new(e)))
+  }
+}
+
+/// A `bincode`-backed codec.
+///
+/// Bincode encodes integers at a fixed width rather than MessagePack's variable-length encoding,
+/// which makes it more compact for the `u64`-heavy offset index and for numeric-heavy payloads.
+pub struct Bincode;
+
+impl Codec for Bincode {
+  fn id() -> u8 {
+    1
+  }
+
+  fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>> {
+    ::bincode::serialize(value).map_err(|e| Error::Codec(Box::new(e)))
+  }
+
+  fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V> {
+    ::bincode::deserialize(bytes).map_err(|e| Error::Codec(Box::new(e)))
+  }
+}
+
+/// The error returned when a table's on-disk codec id doesn't match the [`Codec`] it was opened
+/// with.
+///
+/// [`Codec`]: Codec
+#[derive(Debug)]
+pub(crate) struct CodecMismatch {
+  pub(crate) stored: u8,
+  pub(crate) expected: u8,
+}
+
+impl Display for CodecMismatch {
+  fn fmt(&self, f: &mut Formatter) -> FmtResult {
+    write!(
+      f,
+      "table was written with codec id {}, but was opened expecting codec id {}",
+      self.stored, self.expected,
+    )
+  }
+}
+
+impl StdError for CodecMismatch {}
@@ -1,5 +1,4 @@
-use serde_msgpack::decode;
-use serde_msgpack::encode;
+use failure::Fail;
 
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::io;
@@ -10,16 +9,34 @@ pub type Result<T> = result::Result<T, Error>;
 #[derive(Debug, Fail)]
 pub enum Error {
   Io(io::Error),
-  MsgPackEnc(encode::Error),
-  MsgPackDec(decode::Error),
+  /// An index or data file's signature didn't start with the expected magic bytes.
+  BadMagic,
+  /// A file's signature named a format version newer than this build of `table` understands.
+  UnsupportedVersion(u8),
+  /// An encryption-related error: a plaintext/encrypted mismatch between the caller and a
+  /// table's on-disk flags, or a cipher failure.
+  Crypto(String),
+  /// A [`TimeseriesTable::push_at`] call's timestamp was less than the previously pushed
+  /// timestamp.
+  ///
+  /// [`TimeseriesTable::push_at`]: ::timeseries::TimeseriesTable::push_at
+  OutOfOrder,
+  /// An error from the [`Codec`] a table was opened with: either the underlying encoding library
+  /// failed, or the table was opened with a codec other than the one it was written with.
+  ///
+  /// [`Codec`]: ::codec::Codec
+  Codec(Box<dyn Fail>),
 }
 
 impl Display for Error {
   fn fmt(&self, f: &mut Formatter) -> FmtResult {
     match *self {
       Error::Io(ref e) => write!(f, "{}", e),
-      Error::MsgPackEnc(ref e) => write!(f, "{}", e),
-      Error::MsgPackDec(ref e) => write!(f, "{}", e),
+      Error::BadMagic => write!(f, "file signature did not match the expected magic bytes"),
+      Error::UnsupportedVersion(v) => write!(f, "unsupported file format version {}", v),
+      Error::Crypto(ref e) => write!(f, "{}", e),
+      Error::OutOfOrder => write!(f, "timestamp is out of order"),
+      Error::Codec(ref e) => write!(f, "{}", e),
     }
   }
 }
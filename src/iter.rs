@@ -1,34 +1,52 @@
+use codec::{Codec, MsgPack};
+
 use error::Result;
 use untyped::Table;
 
 use serde::de::DeserializeOwned;
 
 use std::marker::PhantomData;
+use std::vec;
 
 /// An iterator over a [`Table`].
-pub struct TableIterator<'a, V> {
-  pub(crate) table: &'a Table,
+pub struct TableIterator<'a, V, C: Codec = MsgPack> {
+  pub(crate) table: &'a Table<C>,
   pub(crate) pos: usize,
-  pub(crate) offset: u64,
   pub(crate) _phantom: PhantomData<V>,
 }
 
-impl<'a, V> Iterator for TableIterator<'a, V>
+impl<'a, V, C: Codec> Iterator for TableIterator<'a, V, C>
   where V: DeserializeOwned
 {
   type Item = Result<V>;
 
   fn next(&mut self) -> Option<Self::Item> {
-    // get the length of the item
-    let len = self.table.header.get(self.pos)?;
+    // the offsets are read directly out of the index, with no summation
+    let start = *self.table.header.get(self.pos)?;
+    let end = *self.table.header.get(self.pos + 1)?;
 
-    // get the item without doing additional math
-    let ret = self.table.get_at(self.offset, *len);
+    let ret = self.table.get_at(start, end - start);
 
-    // increment the position and offset
-    self.offset += len;
     self.pos += 1;
 
     Some(ret)
   }
 }
+
+/// An iterator over the values removed by [`Table::drain`].
+///
+/// Unlike [`TableIterator`], the removal has already happened by the time this is returned: the
+/// values are decoded and held in memory, not read from disk as the iterator is consumed.
+///
+/// [`Table::drain`]: ::untyped::Table::drain
+pub struct Drain<V> {
+  pub(crate) inner: vec::IntoIter<Result<V>>,
+}
+
+impl<V> Iterator for Drain<V> {
+  type Item = Result<V>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.inner.next()
+  }
+}
@@ -6,24 +6,34 @@
 //! `table` keeps only an index reference in memory at all times. Data is read from the disk on
 //! read, and it is written to the disk on write.
 //!
-//! Data can only be mutated at the end of the collection. This means the only ways to add or remove
-//! data are `push` and `pop`, respectively.
+//! Data is appended with `push` and removed from the end with `pop`. `truncate`, `set`, and
+//! `drain` additionally allow mutating the collection at arbitrary positions, though anything but
+//! a suffix operation costs an O(bytes-after-the-edit) rewrite of the data file's tail.
 //!
 //! `table` creates two files for each opened collection: an index file and a data file. The index
-//! file contains a vector of lengths, while the data file contains MessagePack-serialized data
-//! corresponding to the lengths.
+//! file contains a vector of cumulative byte offsets into the data file (with a trailing sentinel
+//! equal to the total data length), while the data file contains serialized data corresponding to
+//! those offsets, encoded with whichever [`Codec`] the table was opened with.
+//!
+//! [`Codec`]: ::codec::Codec
 
 #[macro_use]
 extern crate failure_derive;
+extern crate bincode;
+extern crate chacha20;
 extern crate failure;
+extern crate rand;
 extern crate rmp_serde as serde_msgpack;
 extern crate serde;
 #[cfg(test)]
 #[macro_use]
 extern crate serde_derive;
 
+mod buffer;
+pub mod codec;
 pub mod error;
 pub mod iter;
+pub mod timeseries;
 pub mod typed;
 pub mod untyped;
 #[cfg(test)]
@@ -31,5 +41,6 @@ extern crate test as std_test;
 #[cfg(test)]
 mod test;
 
+pub use self::timeseries::TimeseriesTable;
 pub use self::typed::TypedTable;
 pub use self::untyped::Table;
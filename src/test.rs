@@ -1,11 +1,15 @@
 use std_test::Bencher;
 
+use error::Error;
+use timeseries::TimeseriesTable;
 use untyped::Table;
 
+use serde_msgpack;
+
 use std::fs::{self, File};
 use std::io::{Read, Seek, SeekFrom};
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 struct Test {
   number: u32,
   boolean: bool,
@@ -30,8 +34,9 @@ fn serialize() {
     boolean: true,
   };
 
-  // these are the bytes that should be produced
+  // these are the bytes that should be produced, after the 10-byte file signature
   let ex_bytes = [0x92, 0x05, 0xc3];
+  let signature_len = 10;
 
   // open the table
   let mut table = Table::open("testerino").unwrap();
@@ -40,15 +45,15 @@ fn serialize() {
   table.push(&test).unwrap();
 
   // create vector
-  let mut data = Vec::with_capacity(3);
+  let mut data = Vec::with_capacity(signature_len + 3);
   // open the data file
   let mut f = File::open("testerino.dat").unwrap();
   // read the entire data file into the vector
   let read = f.read_to_end(&mut data).unwrap();
 
   {
-    // get the data
-    let slice = &data[..read];
+    // get the data, skipping the signature
+    let slice = &data[signature_len..read];
 
     // it should be equal to the bytes we expected
     assert_eq!(slice, ex_bytes);
@@ -69,13 +74,122 @@ fn serialize() {
   // read again
   let read = f.read_to_end(&mut data).unwrap();
 
-  // should be empty
-  assert!(data[..read].is_empty());
+  // only the signature should be left
+  assert_eq!(read, signature_len);
 
   // cleanup
   cleanup("testerino");
 }
 
+#[test]
+fn mutate() {
+  // open the table
+  let mut table = Table::open("testerino_mutate").unwrap();
+  assert!(table.is_empty());
+
+  let tests: Vec<Test> = (0..5).map(|n| Test { number: n, boolean: n % 2 == 0 }).collect();
+  for test in &tests {
+    table.push(test).unwrap();
+  }
+
+  // set(2, ..) with a same-length replacement should take the in-place fast path
+  table.set(2, &Test { number: 2, boolean: false }).unwrap();
+  assert_eq!(table.get::<Test>(2).unwrap(), Some(Test { number: 2, boolean: false }));
+
+  // set(1, ..) with a longer replacement forces the tail to shift down the data file
+  table.set(1, &Test { number: 1_234_567, boolean: true }).unwrap();
+  assert_eq!(table.get::<Test>(1).unwrap(), Some(Test { number: 1_234_567, boolean: true }));
+  // everything after the rewritten element should have survived the shift untouched
+  assert_eq!(table.get::<Test>(2).unwrap(), Some(Test { number: 2, boolean: false }));
+  assert_eq!(table.get::<Test>(3).unwrap(), tests.get(3).cloned());
+  assert_eq!(table.get::<Test>(4).unwrap(), tests.get(4).cloned());
+
+  // drain(1..3) should remove and return exactly those two elements, compacting the rest down
+  let drained: Vec<Test> = table.drain::<Test, _>(1..3).unwrap().collect::<Result<_, _>>().unwrap();
+  assert_eq!(drained, vec![Test { number: 1_234_567, boolean: true }, Test { number: 2, boolean: false }]);
+  assert_eq!(table.len(), 3);
+  assert_eq!(table.get::<Test>(0).unwrap(), tests.get(0).cloned());
+  assert_eq!(table.get::<Test>(1).unwrap(), tests.get(3).cloned());
+  assert_eq!(table.get::<Test>(2).unwrap(), tests.get(4).cloned());
+
+  // truncate(1) should drop everything past index 0
+  table.truncate(1).unwrap();
+  assert_eq!(table.len(), 1);
+  assert_eq!(table.get::<Test>(0).unwrap(), tests.get(0).cloned());
+
+  cleanup("testerino_mutate");
+}
+
+#[test]
+fn encrypted() {
+  let key = [7; 32];
+
+  {
+    let mut table = Table::open_encrypted("testerino_encrypted", key).unwrap();
+    table.push(&Test { number: 9, boolean: true }).unwrap();
+  }
+
+  // reopening with the right key should round-trip the data
+  {
+    let mut table = Table::open_encrypted("testerino_encrypted", key).unwrap();
+    assert_eq!(table.get::<Test>(0).unwrap(), Some(Test { number: 9, boolean: true }));
+  }
+
+  // reopening a plaintext-expecting open() on an encrypted table should be rejected
+  match Table::open("testerino_encrypted") {
+    Err(Error::Crypto(_)) => {},
+    other => panic!("expected Error::Crypto, got {:?}", other),
+  }
+
+  cleanup("testerino_encrypted");
+}
+
+#[test]
+fn legacy_migration() {
+  // a lengths vector with a leading zero-length element is indistinguishable from an offset
+  // vector by shape alone (both are non-decreasing starting at zero): [0, 2, 3] could be legacy
+  // lengths 0, 2, 3 (three elements, five bytes total) or offsets 0, 2, 3 (two elements, three
+  // bytes total). the migration has to cross-check against the data file's actual byte length
+  // (five, here) to tell them apart, rather than trusting the index vector's shape alone
+  let lengths: Vec<u64> = vec![0, 2, 3];
+  fs::write("testerino_legacy.idx", serde_msgpack::to_vec(&lengths).unwrap()).unwrap();
+  // element 0 is zero bytes, element 1 is two bytes of filler, element 2 is a real Test
+  let data: Vec<u8> = vec![0xaa, 0xbb, 0x92, 0x05, 0xc3];
+  fs::write("testerino_legacy.dat", &data).unwrap();
+
+  let table = Table::open("testerino_legacy").unwrap();
+  assert_eq!(table.len(), 3);
+  assert_eq!(table.get::<Test>(2).unwrap(), Some(Test { number: 5, boolean: true }));
+
+  cleanup("testerino_legacy");
+}
+
+#[test]
+fn timeseries() {
+  let mut table = TimeseriesTable::open("testerino_timeseries").unwrap();
+  assert!(table.is_empty());
+
+  table.push_at(10, &Test { number: 1, boolean: true }).unwrap();
+  table.push_at(20, &Test { number: 2, boolean: false }).unwrap();
+  table.push_at(20, &Test { number: 3, boolean: true }).unwrap();
+  table.push_at(30, &Test { number: 4, boolean: false }).unwrap();
+
+  // a timestamp older than the last pushed one is rejected, and nothing is appended
+  match table.push_at(15, &Test { number: 5, boolean: true }) {
+    Err(Error::OutOfOrder) => {},
+    other => panic!("expected Error::OutOfOrder, got {:?}", other),
+  }
+  assert_eq!(table.len(), 4);
+
+  assert_eq!(table.first_after(10), Some(1));
+  assert_eq!(table.last_before(20), Some(0));
+
+  let in_range: Vec<Test> = table.range(20, 20).collect::<Result<_, _>>().unwrap();
+  assert_eq!(in_range, vec![Test { number: 2, boolean: false }, Test { number: 3, boolean: true }]);
+
+  cleanup("testerino_timeseries");
+}
+
 #[bench]
 fn append(b: &mut Bencher) {
   // open table
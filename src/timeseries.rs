@@ -0,0 +1,355 @@
+//! An append-only table whose entries are tagged with a monotonically non-decreasing timestamp.
+//!
+//! This is the time-range-query counterpart to [`TypedTable`]: every entry is stored alongside a
+//! `u64` timestamp, and because timestamps only ever increase, a range of entries can be found
+//! with a binary search over the timestamp column instead of a linear scan.
+//!
+//! [`TypedTable`]: ::typed::TypedTable
+
+use buffer::DataFile;
+
+use codec::{Codec, MsgPack};
+
+use error::{Error, Result};
+use untyped::{DEFAULT_CAPACITY, FORMAT_VERSION, MAGIC, SIGNATURE_LEN, check_codec, codec_id_of, flags_with_codec, signature_bytes, write_header_file};
+
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+
+pub struct TimeseriesTable<V, C: Codec = MsgPack> {
+  // header[x] = (timestamp, byte offset) of element x in the data file
+  // header always has at least one entry; the last entry is a sentinel whose offset is the
+  // total length of the data file (its timestamp is never searched)
+  header: Vec<(u64, u64)>,
+  header_file: RefCell<File>,
+  data: DataFile,
+  len: u64,
+  _phantom: PhantomData<V>,
+  _codec: PhantomData<C>,
+}
+
+impl<V> TimeseriesTable<V, MsgPack>
+  where V: Serialize + DeserializeOwned,
+{
+  /// Open a timeseries table with a given name, creating it on the disk if it doesn't exist.
+  ///
+  /// The data file is buffered with [`DEFAULT_CAPACITY`] bytes of capacity; see
+  /// [`TimeseriesTable::with_capacity`] to choose a different size.
+  ///
+  /// [`DEFAULT_CAPACITY`]: ::untyped::DEFAULT_CAPACITY
+  pub fn open(name: &str) -> Result<TimeseriesTable<V, MsgPack>> {
+    TimeseriesTable::with_capacity(name, DEFAULT_CAPACITY)
+  }
+
+  /// Open a timeseries table with a given name, creating it on the disk if it doesn't exist,
+  /// buffering writes to the data file with `capacity` bytes of capacity.
+  ///
+  /// See [`Table::with_capacity`] for details on the buffering.
+  ///
+  /// [`Table::with_capacity`]: ::untyped::Table::with_capacity
+  pub fn with_capacity(name: &str, capacity: usize) -> Result<TimeseriesTable<V, MsgPack>> {
+    TimeseriesTable::with_capacity_and_codec(name, capacity)
+  }
+}
+
+impl<V, C: Codec> TimeseriesTable<V, C> {
+  /// Open a timeseries table with a given name and an explicit [`Codec`]; see [`Table::with_codec`].
+  ///
+  /// [`Codec`]: ::codec::Codec
+  /// [`Table::with_codec`]: ::untyped::Table::with_codec
+  pub fn with_codec(name: &str) -> Result<TimeseriesTable<V, C>> {
+    TimeseriesTable::with_capacity_and_codec(name, DEFAULT_CAPACITY)
+  }
+
+  /// Like [`TimeseriesTable::with_codec`], but buffers writes to the data file with `capacity`
+  /// bytes of capacity. See [`Table::with_capacity`] for details on the buffering.
+  ///
+  /// [`TimeseriesTable::with_codec`]: TimeseriesTable::with_codec
+  /// [`Table::with_capacity`]: ::untyped::Table::with_capacity
+  pub fn with_capacity_and_codec(name: &str, capacity: usize) -> Result<TimeseriesTable<V, C>> {
+    let mut oo = OpenOptions::new();
+    oo
+      .read(true)
+      .write(true)
+      .create(true);
+
+    let header_file = oo.open(format!("{}.idx", name)).map_err(Error::Io)?;
+    let data_file = oo.open(format!("{}.dat", name)).map_err(Error::Io)?;
+
+    let header = read_index::<C>(&header_file)?;
+    ensure_data_signature::<C>(&data_file)?;
+
+    let len = header.last().map(|&(_, offset)| offset).unwrap_or(0);
+    Ok(TimeseriesTable {
+      header,
+      header_file: RefCell::new(header_file),
+      data: DataFile::new(data_file, capacity, SIGNATURE_LEN, len),
+      len,
+      _phantom: Default::default(),
+      _codec: PhantomData,
+    })
+  }
+
+  /// Gets the length of this table.
+  pub fn len(&self) -> usize {
+    self.header.len() - 1
+  }
+
+  /// Checks if the table is empty.
+  ///
+  /// Equivalent to `table.len() == 0`.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Reads `len` bytes starting at logical `offset` out of the data file.
+  ///
+  /// Flushes any buffered writes first, since the bytes being read may still be sitting in the
+  /// write buffer rather than on disk.
+  fn read_at(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
+    self.data.read_at(offset, len)
+  }
+
+  /// Write the header file to the disk.
+  ///
+  /// Unlike the data file, the header file is not written to until this is called or the table is
+  /// dropped. Calling this will force the header file to be written to the disk.
+  pub fn write_header(&mut self) -> Result<()> {
+    // serialize the header, recording which codec it (and the data file) were written with
+    let body = C::encode(&self.header)?;
+    write_header_file(&self.data, &self.header_file, flags_with_codec(false, C::id()), body)
+  }
+
+  /// Finds the index of the first element whose timestamp is strictly greater than `ts`.
+  ///
+  /// Returns `None` if every element's timestamp is less than or equal to `ts`.
+  pub fn first_after(&self, ts: u64) -> Option<usize> {
+    let pos = upper_bound(&self.header[..self.len()], ts);
+    if pos >= self.len() {
+      None
+    } else {
+      Some(pos)
+    }
+  }
+
+  /// Finds the index of the last element whose timestamp is strictly less than `ts`.
+  ///
+  /// Returns `None` if every element's timestamp is greater than or equal to `ts`.
+  pub fn last_before(&self, ts: u64) -> Option<usize> {
+    let pos = lower_bound(&self.header[..self.len()], ts);
+    if pos == 0 {
+      None
+    } else {
+      Some(pos - 1)
+    }
+  }
+
+  /// Creates an iterator over the elements whose timestamp falls in `start_ts..=end_ts`.
+  ///
+  /// Uses a pair of binary searches over the timestamp column to find the first and last matching
+  /// elements, then only reads that slice, rather than scanning every element in the table.
+  pub fn range(&self, start_ts: u64, end_ts: u64) -> TimeseriesIterator<V, C> {
+    let entries = &self.header[..self.len()];
+    let start = lower_bound(entries, start_ts);
+    let end = upper_bound(entries, end_ts);
+    TimeseriesIterator {
+      table: self,
+      pos: start,
+      end: start.max(end),
+      _codec: PhantomData,
+    }
+  }
+
+  /// Create an iterator over every value in the table, in timestamp order.
+  pub fn iter(&self) -> TimeseriesIterator<V, C> {
+    TimeseriesIterator {
+      table: self,
+      pos: 0,
+      end: self.len(),
+      _codec: PhantomData,
+    }
+  }
+}
+
+impl<V, C: Codec> TimeseriesTable<V, C>
+  where V: Serialize + DeserializeOwned,
+{
+  /// Get an item at position `pos`.
+  ///
+  /// This will return `Ok(None)` if `pos` exceeds the table's bounds.
+  pub fn get(&self, pos: usize) -> Result<Option<V>> {
+    if pos >= self.len() {
+      return Ok(None);
+    }
+
+    let (_, start) = self.header[pos];
+    let (_, end) = self.header[pos + 1];
+    let data = self.read_at(start, end - start)?;
+    C::decode(&data).map(Some)
+  }
+
+  /// Add an item to the end of the table, tagged with timestamp `ts`.
+  ///
+  /// `ts` must be greater than or equal to the timestamp of the previously pushed item; this is
+  /// the invariant [`range`], [`first_after`] and [`last_before`] rely on to binary search
+  /// instead of scanning the whole table. Pushing an out-of-order timestamp returns
+  /// [`Error::OutOfOrder`] and leaves the table unchanged.
+  ///
+  /// [`range`]: TimeseriesTable::range
+  /// [`first_after`]: TimeseriesTable::first_after
+  /// [`last_before`]: TimeseriesTable::last_before
+  /// [`Error::OutOfOrder`]: ::error::Error::OutOfOrder
+  // FIXME: if writing fails, the header is invalid
+  pub fn push_at(&mut self, ts: u64, value: &V) -> Result<()> {
+    if let Some(&(last_ts, _)) = self.header[..self.len()].last() {
+      if ts < last_ts {
+        return Err(Error::OutOfOrder);
+      }
+    }
+
+    // serialize the data
+    let serialized = C::encode(value)?;
+    // get the length of the serialized data
+    let len = serialized.len() as u64;
+    // the new element starts where the old sentinel ended
+    let old_len = self.len;
+    self.len += len;
+    // push the new sentinel, recording the timestamp and the new total length
+    self.header.push((ts, self.len));
+
+    // buffer the new data; this only actually touches the file once the buffer fills up
+    self.data.append(old_len, &serialized)?;
+
+    Ok(())
+  }
+}
+
+/// Finds the index of the first entry whose timestamp is `>= ts`, or `entries.len()` if none is.
+fn lower_bound(entries: &[(u64, u64)], ts: u64) -> usize {
+  let mut lo = 0;
+  let mut hi = entries.len();
+  while lo < hi {
+    let mid = lo + (hi - lo) / 2;
+    if entries[mid].0 < ts {
+      lo = mid + 1;
+    } else {
+      hi = mid;
+    }
+  }
+  lo
+}
+
+/// Finds the index of the first entry whose timestamp is `> ts`, or `entries.len()` if none is.
+fn upper_bound(entries: &[(u64, u64)], ts: u64) -> usize {
+  let mut lo = 0;
+  let mut hi = entries.len();
+  while lo < hi {
+    let mid = lo + (hi - lo) / 2;
+    if entries[mid].0 <= ts {
+      lo = mid + 1;
+    } else {
+      hi = mid;
+    }
+  }
+  lo
+}
+
+/// Reads the on-disk index vector out of `file`, validating the signature block.
+///
+/// Unlike [`untyped::read_index`], there is no legacy format to migrate from: `TimeseriesTable`
+/// didn't exist before the signature block did.
+///
+/// [`untyped::read_index`]: ::untyped
+fn read_index<C: Codec>(file: &File) -> Result<Vec<(u64, u64)>> {
+  let size = file.metadata().map_err(Error::Io)?.len();
+  if size == 0 {
+    return Ok(vec![(0, 0)]);
+  }
+
+  let mut file = file;
+  let mut magic = [0; MAGIC.len()];
+  file.seek(SeekFrom::Start(0)).map_err(Error::Io)?;
+  file.read_exact(&mut magic).map_err(Error::Io)?;
+  if magic != MAGIC {
+    return Err(Error::BadMagic);
+  }
+
+  let mut rest = [0; 2];
+  file.read_exact(&mut rest).map_err(Error::Io)?;
+  if rest[0] > FORMAT_VERSION {
+    return Err(Error::UnsupportedVersion(rest[0]));
+  }
+  check_codec::<C>(codec_id_of(rest[1]))?;
+
+  let mut body = Vec::new();
+  file.read_to_end(&mut body).map_err(Error::Io)?;
+  C::decode(&body)
+}
+
+/// Validates the signature block at the start of the data file, writing one if the file is brand
+/// new.
+fn ensure_data_signature<C: Codec>(file: &File) -> Result<()> {
+  let size = file.metadata().map_err(Error::Io)?.len();
+  if size == 0 {
+    let mut file = file;
+    return file.write_all(&signature_bytes(flags_with_codec(false, C::id()))).map_err(Error::Io);
+  }
+
+  let mut magic = [0; MAGIC.len()];
+  let mut reader = file;
+  reader.seek(SeekFrom::Start(0)).map_err(Error::Io)?;
+  reader.read_exact(&mut magic).map_err(Error::Io)?;
+  if magic != MAGIC {
+    return Err(Error::BadMagic);
+  }
+
+  let mut rest = [0; 2];
+  reader.read_exact(&mut rest).map_err(Error::Io)?;
+  if rest[0] > FORMAT_VERSION {
+    return Err(Error::UnsupportedVersion(rest[0]));
+  }
+  check_codec::<C>(codec_id_of(rest[1]))?;
+
+  Ok(())
+}
+
+/// An iterator over a [`TimeseriesTable`], as returned by [`TimeseriesTable::range`] and
+/// [`TimeseriesTable::iter`].
+pub struct TimeseriesIterator<'a, V, C: Codec = MsgPack> {
+  table: &'a TimeseriesTable<V, C>,
+  pos: usize,
+  end: usize,
+  _codec: PhantomData<C>,
+}
+
+impl<'a, V, C: Codec> Iterator for TimeseriesIterator<'a, V, C>
+  where V: DeserializeOwned,
+{
+  type Item = Result<V>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.pos >= self.end {
+      return None;
+    }
+
+    let (_, start) = self.table.header[self.pos];
+    let (_, end) = self.table.header[self.pos + 1];
+    let ret = self.table.read_at(start, end - start)
+      .and_then(|data| C::decode(&data));
+
+    self.pos += 1;
+
+    Some(ret)
+  }
+}
+
+impl<V, C: Codec> Drop for TimeseriesTable<V, C> {
+  fn drop(&mut self) {
+    self.write_header().ok();
+  }
+}
@@ -3,26 +3,41 @@
 //! Really, this is just a wrapper around a [`Table`] that forces the compiler to use one type for
 //! the generic calls.
 
+use codec::{Codec, MsgPack};
+
 use error::Result;
-use iter::TableIterator;
+use iter::{Drain, TableIterator};
 use untyped::Table;
 
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 
 use std::marker::PhantomData;
+use std::ops::RangeBounds;
 
-pub struct TypedTable<V> {
-  table: Table,
+pub struct TypedTable<V, C: Codec = MsgPack> {
+  table: Table<C>,
   _phantom: PhantomData<V>,
 }
 
-impl<V> TypedTable<V>
+impl<V> TypedTable<V, MsgPack>
   where V: Serialize + DeserializeOwned,
 {
-  pub fn open(name: &str) -> Result<TypedTable<V>> {
+  pub fn open(name: &str) -> Result<TypedTable<V, MsgPack>> {
     Ok(Table::open(name)?.into())
   }
+}
+
+impl<V, C: Codec> TypedTable<V, C>
+  where V: Serialize + DeserializeOwned,
+{
+  /// Open a typed table with a given name and an explicit [`Codec`]; see [`Table::with_codec`].
+  ///
+  /// [`Codec`]: ::codec::Codec
+  /// [`Table::with_codec`]: ::untyped::Table::with_codec
+  pub fn with_codec(name: &str) -> Result<TypedTable<V, C>> {
+    Ok(Table::with_codec(name)?.into())
+  }
 
   pub fn len(&self) -> usize {
     self.table.len()
@@ -44,17 +59,40 @@ impl<V> TypedTable<V>
     self.table.pop()
   }
 
+  /// See [`Table::truncate`].
+  ///
+  /// [`Table::truncate`]: ::untyped::Table::truncate
+  pub fn truncate(&mut self, n: usize) -> Result<()> {
+    self.table.truncate(n)
+  }
+
+  /// See [`Table::set`].
+  ///
+  /// [`Table::set`]: ::untyped::Table::set
+  pub fn set(&mut self, pos: usize, value: &V) -> Result<()> {
+    self.table.set(pos, value)
+  }
+
+  /// See [`Table::drain`].
+  ///
+  /// [`Table::drain`]: ::untyped::Table::drain
+  pub fn drain<R>(&mut self, range: R) -> Result<Drain<V>>
+    where R: RangeBounds<usize>,
+  {
+    self.table.drain(range)
+  }
+
   pub fn write_header(&mut self) -> Result<()> {
     self.table.write_header()
   }
 
-  pub fn iter(&self) -> TableIterator<V> {
+  pub fn iter(&self) -> TableIterator<V, C> {
     self.table.iter()
   }
 }
 
-impl<V> From<Table> for TypedTable<V> {
-  fn from(table: Table) -> TypedTable<V> {
+impl<V, C: Codec> From<Table<C>> for TypedTable<V, C> {
+  fn from(table: Table<C>) -> TypedTable<V, C> {
     TypedTable {
       table,
       _phantom: Default::default(),
@@ -1,7 +1,16 @@
 //! A table with a potentially different type for each entry.
 
+use buffer::DataFile;
+
+use chacha20::{ChaCha20, Key, Nonce};
+use chacha20::cipher::{NewCipher, StreamCipher, StreamCipherSeek};
+
+use codec::{Codec, CodecMismatch, MsgPack};
+
 use error::{Error, Result};
-use iter::TableIterator;
+use iter::{Drain, TableIterator};
+
+use rand::RngCore;
 
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
@@ -11,57 +20,231 @@ use serde_msgpack;
 use std::cell::RefCell;
 use std::fs::{OpenOptions, File};
 use std::io::{Read, Write, Seek, SeekFrom};
-use std::path::Path;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+/// The default capacity, in bytes, of the buffer placed in front of the data file.
+///
+/// See [`Table::with_capacity`] for details.
+pub const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+/// The magic bytes every index and data file starts with.
+///
+/// The first byte has its high bit set so the signature can't be mistaken for plain ASCII, and
+/// the trailing `CR LF SUB LF` run mirrors the trick the PNG format uses to catch files that have
+/// been corrupted by a text-mode transfer or inconsistent line-ending translation.
+pub(crate) const MAGIC: [u8; 8] = [0x8f, b'T', b'B', b'L', b'\r', b'\n', 0x1a, b'\n'];
+
+/// The current on-disk format version, stored in the byte immediately after [`MAGIC`].
+pub(crate) const FORMAT_VERSION: u8 = 1;
+
+/// Total size, in bytes, of the fixed signature block: the magic, a one-byte format version, and
+/// a one-byte flags field.
+pub(crate) const SIGNATURE_LEN: u64 = MAGIC.len() as u64 + 2;
+
+/// Set in the signature's flags byte when the data file is ChaCha20-encrypted. When set, the
+/// signature block is followed by a 96-bit nonce before the payload starts.
+const FLAG_ENCRYPTED: u8 = 0b0000_0001;
+
+/// The bits of the flags byte that store the [`Codec`] id a file was written with.
+const CODEC_MASK: u8 = 0b0000_1110;
+
+/// How far the codec id is shifted left to land in [`CODEC_MASK`].
+const CODEC_SHIFT: u8 = 1;
+
+/// Size, in bytes, of the random nonce stored after the signature block of an encrypted data file.
+const NONCE_LEN: u64 = 12;
+
+/// Packs `encrypted` and a [`Codec`] id into a signature flags byte.
+pub(crate) fn flags_with_codec(encrypted: bool, codec_id: u8) -> u8 {
+  (if encrypted { FLAG_ENCRYPTED } else { 0 }) | ((codec_id << CODEC_SHIFT) & CODEC_MASK)
+}
+
+/// Extracts the [`Codec`] id packed into a signature flags byte by [`flags_with_codec`].
+pub(crate) fn codec_id_of(flags: u8) -> u8 {
+  (flags & CODEC_MASK) >> CODEC_SHIFT
+}
+
+/// Checks that `stored`, a codec id read out of a file's signature, matches the codec the caller
+/// opened the table with.
+pub(crate) fn check_codec<C: Codec>(stored: u8) -> Result<()> {
+  if stored == C::id() {
+    Ok(())
+  } else {
+    Err(Error::Codec(Box::new(CodecMismatch { stored, expected: C::id() })))
+  }
+}
 
 /// A table.
 ///
 /// Tables function similarly to [`Vec`]s.
 ///
+/// The `C` type parameter selects the [`Codec`] used to serialize the index and each element;
+/// it defaults to [`MsgPack`], the original format. [`Table::open`] and friends only construct
+/// `Table<MsgPack>`; use [`Table::with_codec`] and friends to pick a different one.
+///
 /// [`Vec`]: ::std::vec::Vec
-#[derive(Debug)]
-pub struct Table {
-  // header[x] = length of element at data[x]
-  // sum(header[..x])..sum(header[..x]) + header[x] = data[x]
+pub struct Table<C: Codec = MsgPack> {
+  // header[x] = byte offset of element x in the data file
+  // header[x]..header[x + 1] = data[x]
+  // header always has at least one entry; the last entry is a sentinel equal to the total
+  // length of the data file (and to `len`)
   pub(crate) header: Vec<u64>,
   header_file: RefCell<File>,
-  data_file: RefCell<File>,
+  data: DataFile,
   len: u64,
+  // ChaCha20 keystream used to decrypt/encrypt the data file, if this table was opened with
+  // `open_encrypted`/`with_capacity_encrypted`
+  cipher: Option<RefCell<ChaCha20>>,
+  _codec: PhantomData<C>,
 }
 
-impl Table {
+impl Table<MsgPack> {
   /// Open a table with a given name, creating it on the disk if it doesn't exist.
-  pub fn open(name: &str) -> Result<Table> {
+  ///
+  /// The data file is buffered with [`DEFAULT_CAPACITY`] bytes of capacity; see
+  /// [`Table::with_capacity`] to choose a different size.
+  pub fn open(name: &str) -> Result<Table<MsgPack>> {
+    Table::with_capacity(name, DEFAULT_CAPACITY)
+  }
+
+  /// Open a table with a given name, creating it on the disk if it doesn't exist, buffering
+  /// writes to the data file with `capacity` bytes of capacity.
+  ///
+  /// Buffering lets consecutive [`push`]es accumulate in memory instead of seeking and writing
+  /// to the data file on every call; the buffer is flushed whenever it fills up, and also
+  /// whenever the table needs to read data that may still be sitting in it (see [`get`],
+  /// [`get_at`], [`iter`] and [`pop`]), inside [`write_header`], and when the table is dropped.
+  ///
+  /// [`push`]: Table::push
+  /// [`get`]: Table::get
+  /// [`get_at`]: Table::get_at
+  /// [`iter`]: Table::iter
+  /// [`pop`]: Table::pop
+  /// [`write_header`]: Table::write_header
+  pub fn with_capacity(name: &str, capacity: usize) -> Result<Table<MsgPack>> {
+    Table::open_with(name, capacity, None)
+  }
+
+  /// Open an encrypted table with a given name, creating it on the disk if it doesn't exist.
+  ///
+  /// The data file's contents are encrypted at rest with ChaCha20 under `key`; the index file is
+  /// left in plaintext, since it only holds offsets. Opening an existing encrypted table with the
+  /// wrong method (or an existing plaintext table with this one) returns [`Error::Crypto`].
+  ///
+  /// [`Error::Crypto`]: ::error::Error::Crypto
+  pub fn open_encrypted(name: &str, key: [u8; 32]) -> Result<Table<MsgPack>> {
+    Table::with_capacity_encrypted(name, DEFAULT_CAPACITY, key)
+  }
+
+  /// Like [`Table::open_encrypted`], but buffers writes to the data file with `capacity` bytes of
+  /// capacity. See [`Table::with_capacity`] for details on the buffering.
+  ///
+  /// [`Table::open_encrypted`]: Table::open_encrypted
+  /// [`Table::with_capacity`]: Table::with_capacity
+  pub fn with_capacity_encrypted(name: &str, capacity: usize, key: [u8; 32]) -> Result<Table<MsgPack>> {
+    Table::open_with(name, capacity, Some(key))
+  }
+}
+
+impl<C: Codec> Table<C> {
+  /// Open a table with a given name and an explicit [`Codec`], creating it on the disk if it
+  /// doesn't exist.
+  ///
+  /// Reopening a table with a codec other than the one it was written with returns
+  /// [`Error::Codec`]. See [`Table::open`] for the default-codec equivalent.
+  ///
+  /// [`Error::Codec`]: ::error::Error::Codec
+  /// [`Table::open`]: Table::open
+  pub fn with_codec(name: &str) -> Result<Table<C>> {
+    Table::with_capacity_and_codec(name, DEFAULT_CAPACITY)
+  }
+
+  /// Like [`Table::with_codec`], but buffers writes to the data file with `capacity` bytes of
+  /// capacity. See [`Table::with_capacity`] for details on the buffering.
+  ///
+  /// [`Table::with_codec`]: Table::with_codec
+  /// [`Table::with_capacity`]: Table::with_capacity
+  pub fn with_capacity_and_codec(name: &str, capacity: usize) -> Result<Table<C>> {
+    Table::open_with(name, capacity, None)
+  }
+
+  /// Like [`Table::with_codec`], but also encrypts the data file at rest; see
+  /// [`Table::open_encrypted`].
+  ///
+  /// [`Table::with_codec`]: Table::with_codec
+  /// [`Table::open_encrypted`]: Table::open_encrypted
+  pub fn with_codec_encrypted(name: &str, key: [u8; 32]) -> Result<Table<C>> {
+    Table::with_capacity_and_codec_encrypted(name, DEFAULT_CAPACITY, key)
+  }
+
+  /// The combination of [`Table::with_capacity_and_codec`] and [`Table::with_codec_encrypted`].
+  ///
+  /// [`Table::with_capacity_and_codec`]: Table::with_capacity_and_codec
+  /// [`Table::with_codec_encrypted`]: Table::with_codec_encrypted
+  pub fn with_capacity_and_codec_encrypted(name: &str, capacity: usize, key: [u8; 32]) -> Result<Table<C>> {
+    Table::open_with(name, capacity, Some(key))
+  }
+
+  fn open_with(name: &str, capacity: usize, key: Option<[u8; 32]>) -> Result<Table<C>> {
     let mut oo = OpenOptions::new();
     oo
       .read(true)
       .write(true)
       .create(true);
 
-    let idx = format!("{}.idx", name);
-    let p = Path::new(&idx);
-
-    let existed = p.exists();
+    let header_file = oo.open(format!("{}.idx", name)).map_err(Error::Io)?;
+    let data_file = oo.open(format!("{}.dat", name)).map_err(Error::Io)?;
 
-    let header_file = RefCell::new(oo.open(p).map_err(Error::Io)?);
-    let data_file = RefCell::new(oo.open(format!("{}.dat", name)).map_err(Error::Io)?);
+    // needed to disambiguate a pre-signature index vector; see `read_index`
+    let data_len = data_file.metadata().map_err(Error::Io)?.len();
+    let header = read_index::<C>(&header_file, data_len)?;
+    let nonce = ensure_data_signature::<C>(&data_file, key.as_ref())?;
 
-    let header = if existed {
-      serde_msgpack::from_read(&*header_file.borrow()).map_err(Error::MsgPackDec)?
-    } else {
-      Vec::new()
+    let data_prefix_len = SIGNATURE_LEN + if nonce.is_some() { NONCE_LEN } else { 0 };
+    let cipher = match (key, nonce) {
+      (Some(key), Some(nonce)) => {
+        Some(RefCell::new(ChaCha20::new(Key::from_slice(&key), Nonce::from_slice(&nonce))))
+      },
+      _ => None,
     };
-    let len = header.iter().sum();
+
+    let len = *header.last().unwrap_or(&0);
     Ok(Table {
       header,
-      header_file,
-      data_file,
+      header_file: RefCell::new(header_file),
+      data: DataFile::new(data_file, capacity, data_prefix_len, len),
       len,
+      cipher,
+      _codec: PhantomData,
     })
   }
 
   /// Gets the length of this table.
   pub fn len(&self) -> usize {
-    self.header.len()
+    self.header.len() - 1
+  }
+
+  /// Gets the byte offset in the data file at which element `pos` starts.
+  ///
+  /// `pos == self.len()` is also valid and returns the offset just past the last element,
+  /// i.e. the total length of the data file.
+  pub fn offset_of(&self, pos: usize) -> Option<u64> {
+    self.header.get(pos).cloned()
+  }
+
+  /// Finds the index of the element occupying byte offset `byte` in the data file.
+  ///
+  /// Returns `None` if `byte` is beyond the end of the data file.
+  pub fn index_at_offset(&self, byte: u64) -> Option<usize> {
+    if byte >= self.len {
+      return None;
+    }
+
+    match self.header.binary_search(&byte) {
+      Ok(pos) => Some(pos),
+      Err(pos) => Some(pos - 1),
+    }
   }
 
   /// Checks if the table is empty.
@@ -79,35 +262,41 @@ impl Table {
     where V: DeserializeOwned,
   {
     // check if the header has an entry for the position
-    if pos >= self.header.len() {
+    if pos >= self.len() {
       return Ok(None);
     }
 
-    // sum all header entries before the position
-    let start: u64 = self.header[..pos].iter().sum();
-    // get the header entry at the position
-    let len = self.header[pos];
-    // seek to the start of the data
-    self.data_file.borrow_mut().seek(SeekFrom::Start(start)).map_err(Error::Io)?;
-    // create a vector to store the data
-    let mut data = vec![0; len as usize];
-    // read the data
-    self.data_file.borrow_mut().read_exact(&mut data).map_err(Error::Io)?;
+    // the offset of the position is read directly out of the index, with no summation
+    let start = self.header[pos];
+    let len = self.header[pos + 1] - start;
+    let data = self.read_at(start, len)?;
     // attempt to deserialize the data
-    serde_msgpack::from_slice(&data).map_err(Error::MsgPackDec).map(Some)
+    C::decode(&data).map(Some)
   }
 
   pub fn get_at<V>(&self, offset: u64, len: u64) -> Result<V>
     where V: DeserializeOwned
   {
-    // seek to the offset
-    self.data_file.borrow_mut().seek(SeekFrom::Start(offset)).map_err(Error::Io)?;
-    // create a vector with enough room for the data
-    let mut data = vec![0; len as usize];
-    // read in the data
-    self.data_file.borrow_mut().read_exact(&mut data).map_err(Error::Io)?;
+    let data = self.read_at(offset, len)?;
     // attempt to deserialize
-    serde_msgpack::from_slice(&data).map_err(Error::MsgPackDec)
+    C::decode(&data)
+  }
+
+  /// Reads `len` bytes starting at logical `offset` out of the data file.
+  ///
+  /// Flushes any buffered writes first, since the bytes being read may still be sitting in the
+  /// write buffer rather than on disk. `offset` is relative to the start of the element data,
+  /// i.e. it does not include the fixed data file prefix (signature and, if encrypted, nonce).
+  fn read_at(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
+    let mut data = self.data.read_at(offset, len)?;
+
+    if let Some(cipher) = &self.cipher {
+      let mut cipher = cipher.borrow_mut();
+      cipher.seek(offset);
+      cipher.apply_keystream(&mut data);
+    }
+
+    Ok(data)
   }
 
   /// Add an item to the end of the table.
@@ -116,22 +305,23 @@ impl Table {
     where V: Serialize,
   {
     // serialize the data
-    let serialized = serde_msgpack::to_vec(value).map_err(Error::MsgPackEnc)?;
+    let mut serialized = C::encode(value)?;
     // get the length of the serialized data
-    let len = serialized.len();
-    // add the length to the header
-    self.header.push(len as u64);
-    // add length to master length
-    self.len += len as u64;
-    // get the size of the data file according to the updated header
-    // let size: u64 = self.header.iter().sum();
-    let mut data_file = self.data_file.borrow_mut();
-    // set the data file's length
-    data_file.set_len(self.len).map_err(Error::Io)?;
-    // seek to the start of the new data
-    data_file.seek(SeekFrom::End(-(len as i64))).map_err(Error::Io)?;
-    // write the new data
-    data_file.write_all(&serialized).map_err(Error::Io)?;
+    let len = serialized.len() as u64;
+    // the new element starts where the old sentinel ended
+    let old_len = self.len;
+    self.len += len;
+    // push the new sentinel, recording the new total length
+    self.header.push(self.len);
+
+    if let Some(cipher) = &self.cipher {
+      let mut cipher = cipher.borrow_mut();
+      cipher.seek(old_len);
+      cipher.apply_keystream(&mut serialized);
+    }
+
+    // buffer the new data; this only actually touches the file once the buffer fills up
+    self.data.append(old_len, &serialized)?;
 
     Ok(())
   }
@@ -141,36 +331,189 @@ impl Table {
   /// If a deserialization error occurs, the data that was popped will be lost.
   ///
   /// This will return `Ok(None)` if the table is empty.
+  ///
+  /// This forces a flush of any buffered writes before truncating the data file.
   pub fn pop<V>(&mut self) -> Result<Option<V>>
     where V: DeserializeOwned,
   {
-    // make sure there's data to pop
-    if self.header.is_empty() {
+    // make sure there's an element to pop (the header always has at least the sentinel)
+    if self.len() == 0 {
       return Ok(None);
     }
 
-    // pop the length from the header
-    // we just checked to make sure the header's not empty
-    let len = self.header.pop().unwrap();
+    // drop the sentinel for the popped element; the new last entry becomes both the start of
+    // the popped element and the new sentinel
+    let end = self.header.pop().unwrap();
+    let start = *self.header.last().unwrap();
+    let len = end - start;
+    self.len = start;
 
-    // calculate start point (note that in order to truncate, we have to do this, so we can't just
-    // relative seek from the end of the file)
-    // let start: u64 = self.header[..self.header.len()].iter().sum();
-    let start = self.len - self.header[self.header.len() - 1];
-    let mut data_file = self.data_file.borrow_mut();
-    // seek to the start
-    data_file.seek(SeekFrom::Start(start)).map_err(Error::Io)?;
+    // read_at flushes the write buffer before reading, so this always sees the popped bytes
+    let data = self.read_at(start, len)?;
 
-    // allocate a vec to store the data
-    let mut data = vec![0; len as usize];
-    // read the data
-    data_file.read_exact(&mut data).map_err(Error::Io)?;
-
-    // truncate the file
-    data_file.set_len(start).map_err(Error::Io)?;
+    // truncate the file to drop the popped bytes
+    self.data.set_len(start)?;
 
     // attempt to deserialize
-    serde_msgpack::from_slice(&data).map_err(Error::MsgPackDec).map(Some)
+    C::decode(&data).map(Some)
+  }
+
+  /// Drop every element past index `n`, truncating both the index and the data file to the
+  /// offset of element `n`.
+  ///
+  /// Does nothing if `n >= self.len()`.
+  ///
+  /// This forces a flush of any buffered writes before truncating the data file.
+  pub fn truncate(&mut self, n: usize) -> Result<()> {
+    if n >= self.len() {
+      return Ok(());
+    }
+
+    // the new sentinel is just the start offset of the first element being dropped
+    let new_len = self.header[n];
+    self.header.truncate(n + 1);
+    self.len = new_len;
+
+    self.data.set_len(new_len)?;
+
+    Ok(())
+  }
+
+  /// Replace the element at `pos` with `value`.
+  ///
+  /// Does nothing if `pos >= self.len()`.
+  ///
+  /// If `value` serializes to the same number of bytes as the element it's replacing, this
+  /// overwrites it in place. Otherwise, every byte of the data file after `pos` has to shift, so
+  /// this is O(bytes after `pos`) rather than O(size of `value`): it reads the whole tail of the
+  /// data file into memory, rewrites it after the new element, and updates every later offset in
+  /// the index.
+  pub fn set<V>(&mut self, pos: usize, value: &V) -> Result<()>
+    where V: Serialize,
+  {
+    if pos >= self.len() {
+      return Ok(());
+    }
+
+    let start = self.header[pos];
+    let old_end = self.header[pos + 1];
+    let old_len = old_end - start;
+
+    let mut serialized = C::encode(value)?;
+    let new_len = serialized.len() as u64;
+
+    if new_len == old_len {
+      // fast path: the replacement is the same size, so nothing after it needs to move
+      if let Some(cipher) = &self.cipher {
+        let mut cipher = cipher.borrow_mut();
+        cipher.seek(start);
+        cipher.apply_keystream(&mut serialized);
+      }
+
+      self.data.write_at(start, &serialized)?;
+
+      return Ok(());
+    }
+
+    // slow path: the replacement's length differs, so the tail of the data file has to shift
+    let delta = new_len as i64 - old_len as i64;
+    let total_len = self.len;
+    let tail_len = total_len - old_end;
+
+    // read_at flushes the write buffer before reading, so this always sees the current tail
+    let mut tail = self.read_at(old_end, tail_len)?;
+
+    if let Some(cipher) = &self.cipher {
+      let mut cipher = cipher.borrow_mut();
+      cipher.seek(start);
+      cipher.apply_keystream(&mut serialized);
+      // the tail is moving to a new offset, so it has to be re-encrypted under that offset too
+      let new_tail_offset = (old_end as i64 + delta) as u64;
+      cipher.seek(new_tail_offset);
+      cipher.apply_keystream(&mut tail);
+    }
+
+    let new_total = (total_len as i64 + delta) as u64;
+    serialized.extend_from_slice(&tail);
+    self.data.write_at(start, &serialized)?;
+    self.data.set_len(new_total)?;
+
+    // every offset from `pos + 1` onward (including the sentinel) shifts by `delta`
+    for offset in &mut self.header[pos + 1..] {
+      *offset = (*offset as i64 + delta) as u64;
+    }
+    self.len = new_total;
+
+    Ok(())
+  }
+
+  /// Remove the elements in `range`, returning an iterator over their deserialized values.
+  ///
+  /// Unlike [`Vec::drain`], the removal and compaction happen immediately, not as the returned
+  /// iterator is consumed or dropped. Removing anything but a suffix of the table is O(bytes
+  /// after the range) because the surviving tail of the data file has to shift down to fill the
+  /// gap left behind.
+  ///
+  /// A range end past `self.len()` is clamped rather than panicking; an empty or inverted range
+  /// removes nothing.
+  ///
+  /// [`Vec::drain`]: ::std::vec::Vec::drain
+  pub fn drain<V, R>(&mut self, range: R) -> Result<Drain<V>>
+    where V: DeserializeOwned,
+          R: RangeBounds<usize>,
+  {
+    let len = self.len();
+    let start = match range.start_bound() {
+      Bound::Included(&n) => n,
+      Bound::Excluded(&n) => n + 1,
+      Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+      Bound::Included(&n) => n + 1,
+      Bound::Excluded(&n) => n,
+      Bound::Unbounded => len,
+    }.min(len);
+
+    if start >= end {
+      return Ok(Drain { inner: Vec::new().into_iter() });
+    }
+
+    let removed_start = self.header[start];
+    let removed_end = self.header[end];
+    let removed_len = removed_end - removed_start;
+    let tail_len = self.len - removed_end;
+
+    // read_at flushes the write buffer before reading, so these always see current data
+    let removed_bytes = self.read_at(removed_start, removed_len)?;
+    let mut tail = self.read_at(removed_end, tail_len)?;
+
+    let mut values = Vec::with_capacity(end - start);
+    let mut cursor = 0usize;
+    for i in start..end {
+      let elem_len = (self.header[i + 1] - self.header[i]) as usize;
+      values.push(C::decode(&removed_bytes[cursor..cursor + elem_len]));
+      cursor += elem_len;
+    }
+
+    if let Some(cipher) = &self.cipher {
+      let mut cipher = cipher.borrow_mut();
+      // the tail is moving down to close the gap, so it has to be re-encrypted at its new offset
+      cipher.seek(removed_start);
+      cipher.apply_keystream(&mut tail);
+    }
+
+    self.data.write_at(removed_start, &tail)?;
+    self.data.set_len(removed_start + tail_len)?;
+
+    // offsets before `start` are untouched; the removed entries are dropped, and every offset
+    // from `end` onward shifts down by the removed span's length
+    let mut new_header = Vec::with_capacity(self.header.len() - (end - start));
+    new_header.extend_from_slice(&self.header[..start]);
+    new_header.extend(self.header[end..].iter().map(|offset| offset - removed_len));
+    self.header = new_header;
+    self.len -= removed_len;
+
+    Ok(Drain { inner: values.into_iter() })
   }
 
   /// Write the header file to the disk.
@@ -178,30 +521,257 @@ impl Table {
   /// Unlike the data file, the header file is not written to until this is called or the table is
   /// dropped. Calling this will force the header file to be written to the disk.
   pub fn write_header(&mut self) -> Result<()> {
-    // serialize the header
-    let header = serde_msgpack::to_vec(&self.header).map_err(Error::MsgPackEnc)?;
-    // set the length of the header file
-    self.header_file.borrow_mut().set_len(header.len() as u64).map_err(Error::Io)?;
-    // seek to the beginning
-    self.header_file.borrow_mut().seek(SeekFrom::Start(0)).map_err(Error::Io)?;
-    // write the header
-    self.header_file.borrow_mut().write_all(&header).map_err(Error::Io)?;
-
-    Ok(())
+    // serialize the header; the index is always stored in plaintext, even for an encrypted
+    // table, but it still records which codec it (and the data file) were written with
+    let body = C::encode(&self.header)?;
+    write_header_file(&self.data, &self.header_file, flags_with_codec(false, C::id()), body)
   }
 
   /// Create an iterator over the values in the table.
-  pub fn iter<V>(&self) -> TableIterator<V> {
+  pub fn iter<V>(&self) -> TableIterator<V, C> {
     TableIterator {
       table: self,
       pos: 0,
-      offset: 0,
       _phantom: Default::default(),
     }
   }
 }
 
-impl Drop for Table {
+/// Disambiguates a raw pre-signature `.idx` vector as either the offset format (chunk0-1 and
+/// later) or the legacy per-element-length format, by checking which interpretation's implied
+/// total length actually matches `data_len`, the real size of the (also pre-signature, and so
+/// unprefixed) data file on disk.
+///
+/// Shape alone isn't enough to tell the two apart: a legacy lengths vector whose first pushed
+/// element serialized to zero bytes is also non-decreasing starting at zero, which looks exactly
+/// like an already-migrated offset vector. An offset vector always starts at 0 (position 0 begins
+/// at the start of the data file) and its last entry has to equal the data file's actual byte
+/// length, so both are checked before trusting a raw vector as already-migrated; otherwise it's
+/// read as a lengths vector if its sum matches instead. An empty raw vector — an empty legacy
+/// header, predating even the first pushed element — is always the empty table, not a reading
+/// that needs disambiguating. Neither total matching is reported as [`Error::BadMagic`].
+///
+/// The one case this still can't resolve is a two-entry raw vector whose first entry is zero:
+/// `[0, n]` sums to `n` and also ends in `n`, so both readings imply the same on-disk length no
+/// matter what `n` is, and the only difference is a phantom zero-length element at the front
+/// under the lengths reading. This never arises in practice, since every pre-signature file was
+/// written with [`MsgPack`], which can't encode any value in zero bytes.
+///
+/// [`Error::BadMagic`]: ::error::Error::BadMagic
+fn resolve_legacy_index(raw: Vec<u64>, data_len: u64) -> Result<Vec<u64>> {
+  if raw.is_empty() {
+    return Ok(vec![0]);
+  }
+
+  if raw[0] == 0 && raw.last().cloned() == Some(data_len) {
+    return Ok(raw);
+  }
+
+  if raw.iter().sum::<u64>() == data_len {
+    return Ok(lengths_to_offsets(&raw));
+  }
+
+  Err(Error::BadMagic)
+}
+
+/// Converts a legacy vector of per-element lengths into the offset index format.
+fn lengths_to_offsets(lengths: &[u64]) -> Vec<u64> {
+  let mut offsets = Vec::with_capacity(lengths.len() + 1);
+  let mut total = 0;
+  offsets.push(total);
+  for &len in lengths {
+    total += len;
+    offsets.push(total);
+  }
+  offsets
+}
+
+/// Builds the fixed signature block written at the start of both the index and data files.
+pub(crate) fn signature_bytes(flags: u8) -> Vec<u8> {
+  let mut sig = Vec::with_capacity(SIGNATURE_LEN as usize);
+  sig.extend_from_slice(&MAGIC);
+  sig.push(FORMAT_VERSION);
+  sig.push(flags);
+  sig
+}
+
+/// Writes `header_file`'s contents to `flags`-prefixed signature bytes followed by `body`.
+///
+/// Shared by [`Table::write_header`] and [`TimeseriesTable::write_header`], which only differ in
+/// what they encode into `body`. Flushes `data` first, since the header is only meaningful once
+/// the data it references is durable.
+///
+/// [`Table::write_header`]: Table::write_header
+/// [`TimeseriesTable::write_header`]: ::timeseries::TimeseriesTable::write_header
+pub(crate) fn write_header_file(data: &DataFile, header_file: &RefCell<File>, flags: u8, body: Vec<u8>) -> Result<()> {
+  data.flush()?;
+
+  let mut header = signature_bytes(flags);
+  header.extend_from_slice(&body);
+  let mut header_file = header_file.borrow_mut();
+  header_file.set_len(header.len() as u64).map_err(Error::Io)?;
+  header_file.seek(SeekFrom::Start(0)).map_err(Error::Io)?;
+  header_file.write_all(&header).map_err(Error::Io)?;
+
+  Ok(())
+}
+
+/// Reads the on-disk index vector out of `file`, validating and skipping the signature block if
+/// one is present, or migrating a file that predates it.
+///
+/// `data_len` is the current byte length of the table's (also pre-signature, if `file` is)
+/// data file, used to disambiguate a pre-signature index; see [`resolve_legacy_index`].
+///
+/// [`resolve_legacy_index`]: resolve_legacy_index
+fn read_index<C: Codec>(file: &File, data_len: u64) -> Result<Vec<u64>> {
+  let size = file.metadata().map_err(Error::Io)?.len();
+  if size == 0 {
+    return Ok(vec![0]);
+  }
+
+  let mut file = file;
+  if size >= MAGIC.len() as u64 {
+    let mut magic = [0; MAGIC.len()];
+    file.seek(SeekFrom::Start(0)).map_err(Error::Io)?;
+    file.read_exact(&mut magic).map_err(Error::Io)?;
+
+    if magic == MAGIC {
+      if size < SIGNATURE_LEN {
+        return Err(Error::BadMagic);
+      }
+      let mut rest = [0; 2];
+      file.read_exact(&mut rest).map_err(Error::Io)?;
+      if rest[0] > FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(rest[0]));
+      }
+      check_codec::<C>(codec_id_of(rest[1]))?;
+
+      let mut body = Vec::with_capacity((size - SIGNATURE_LEN) as usize);
+      file.read_to_end(&mut body).map_err(Error::Io)?;
+      return C::decode(&body);
+    }
+
+    if magic[0] == MAGIC[0] {
+      return Err(Error::BadMagic);
+    }
+  }
+
+  // a file that predates the signature: the whole file is a bare msgpack vector, either offsets
+  // (chunk0-1 and later) or lengths (the original format). pre-signature files always used
+  // MessagePack, since the codec abstraction didn't exist yet
+  check_codec::<C>(MsgPack::id())?;
+  file.seek(SeekFrom::Start(0)).map_err(Error::Io)?;
+  let raw: Vec<u64> = serde_msgpack::from_read(file).map_err(|e| Error::Codec(Box::new(e)))?;
+  resolve_legacy_index(raw, data_len)
+}
+
+/// Validates the signature block at the start of the data file, writing one if the file is brand
+/// new or migrating it in place if the file predates the signature.
+///
+/// `key` is `Some` when the table is being opened with [`Table::open_encrypted`]/
+/// [`Table::with_capacity_encrypted`]. Returns the encryption nonce stored in the signature if the
+/// data file is (or is being made) encrypted, `None` otherwise. A plaintext/encrypted mismatch
+/// between the caller and the file's own flags is reported as [`Error::Crypto`]; a mismatch
+/// between `C` and the codec the file was written with is reported as [`Error::Codec`].
+///
+/// [`Table::open_encrypted`]: Table::open_encrypted
+/// [`Table::with_capacity_encrypted`]: Table::with_capacity_encrypted
+/// [`Error::Crypto`]: ::error::Error::Crypto
+/// [`Error::Codec`]: ::error::Error::Codec
+fn ensure_data_signature<C: Codec>(file: &File, key: Option<&[u8; 32]>) -> Result<Option<[u8; 12]>> {
+  let size = file.metadata().map_err(Error::Io)?.len();
+  if size == 0 {
+    let mut file = file;
+    return match key {
+      Some(_) => {
+        let mut nonce = [0; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        file.write_all(&signature_bytes(flags_with_codec(true, C::id()))).map_err(Error::Io)?;
+        file.write_all(&nonce).map_err(Error::Io)?;
+        Ok(Some(nonce))
+      },
+      None => {
+        file.write_all(&signature_bytes(flags_with_codec(false, C::id()))).map_err(Error::Io)?;
+        Ok(None)
+      },
+    };
+  }
+
+  if size >= MAGIC.len() as u64 {
+    let mut magic = [0; MAGIC.len()];
+    let mut reader = file;
+    reader.seek(SeekFrom::Start(0)).map_err(Error::Io)?;
+    reader.read_exact(&mut magic).map_err(Error::Io)?;
+
+    if magic == MAGIC {
+      if size < SIGNATURE_LEN {
+        return Err(Error::BadMagic);
+      }
+      let mut rest = [0; 2];
+      reader.read_exact(&mut rest).map_err(Error::Io)?;
+      let (version, flags) = (rest[0], rest[1]);
+      if version > FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+      }
+      check_codec::<C>(codec_id_of(flags))?;
+
+      return match (flags & FLAG_ENCRYPTED != 0, key) {
+        (true, Some(_)) => {
+          if size < SIGNATURE_LEN + NONCE_LEN {
+            return Err(Error::BadMagic);
+          }
+          let mut nonce = [0; 12];
+          reader.read_exact(&mut nonce).map_err(Error::Io)?;
+          Ok(Some(nonce))
+        },
+        (false, None) => Ok(None),
+        (true, None) => {
+          Err(Error::Crypto("table is encrypted; open it with Table::open_encrypted".into()))
+        },
+        (false, Some(_)) => {
+          Err(Error::Crypto("table is not encrypted; open it with Table::open".into()))
+        },
+      };
+    }
+
+    if magic[0] == MAGIC[0] {
+      return Err(Error::BadMagic);
+    }
+  }
+
+  // a data file that predates the signature can't have been encrypted, and always used
+  // MessagePack, since neither the encryption nor the codec abstraction existed yet
+  if key.is_some() {
+    return Err(Error::Crypto("cannot open a table that predates the signature as encrypted".into()));
+  }
+  check_codec::<C>(MsgPack::id())?;
+
+  // a data file that predates the signature: prepend one, leaving the existing payload bytes
+  // (and thus every offset already recorded in the index) untouched
+  let mut existing = Vec::with_capacity(size as usize);
+  let mut f = file;
+  f.seek(SeekFrom::Start(0)).map_err(Error::Io)?;
+  f.read_to_end(&mut existing).map_err(Error::Io)?;
+
+  f.seek(SeekFrom::Start(0)).map_err(Error::Io)?;
+  f.write_all(&signature_bytes(flags_with_codec(false, C::id()))).map_err(Error::Io)?;
+  f.write_all(&existing).map_err(Error::Io)?;
+
+  Ok(None)
+}
+
+impl<C: Codec> ::std::fmt::Debug for Table<C> {
+  // the cipher doesn't implement Debug, and wouldn't be safe to print anyway
+  fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+    f.debug_struct("Table")
+      .field("header", &self.header)
+      .field("len", &self.len)
+      .field("encrypted", &self.cipher.is_some())
+      .finish()
+  }
+}
+
+impl<C: Codec> Drop for Table<C> {
   fn drop(&mut self) {
     self.write_header().ok();
   }